@@ -0,0 +1,247 @@
+use crate::{AppState, BODY_CHUNK_TARGET, mw::StreamWithLoggedEnd};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use std::{
+    convert::Infallible,
+    fmt,
+    fmt::Write as _,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{BroadcastStream, errors::BroadcastStreamRecvError};
+use tracing::{Event, Level, Span, Subscriber, field::Field};
+use tracing_subscriber::{Layer, layer::Context as LayerContext};
+
+/// Depth of the log tailing channel; a slow `/logs` viewer drops lines rather
+/// than holding up tracing on the request-handling side.
+const CHANNEL_CAPACITY: usize = 1024;
+
+pub fn channel() -> broadcast::Sender<LogRecord> {
+    broadcast::channel(CHANNEL_CAPACITY).0
+}
+
+/// One formatted tracing event, as broadcast to any live `/logs` viewers.
+#[derive(Clone)]
+pub struct LogRecord {
+    level: Level,
+    target: String,
+    message: String,
+}
+impl LogRecord {
+    fn included_by(&self, directive: &Directive) -> bool {
+        match &directive.target {
+            Some(target) if !self.target.starts_with(target.as_str()) => false,
+            _ => self.level <= directive.level,
+        }
+    }
+    fn render(&self, fmt: LogFormat, start: Instant) -> Bytes {
+        let line = match fmt {
+            LogFormat::Fmt => format!("{:>5} {}: {}\n", self.level, self.target, self.message),
+            LogFormat::Profile => format!(
+                "{:>10.3}s {:>5} {}: {}\n",
+                start.elapsed().as_secs_f64(),
+                self.level,
+                self.target,
+                self.message
+            ),
+        };
+        Bytes::from(line)
+    }
+}
+
+/// A [`tracing_subscriber::Layer`] that re-emits events onto a broadcast
+/// channel, regardless of what the console/file layer would have shown, so
+/// `/logs` can apply its own per-viewer level and target filter. Events are
+/// only sent while at least one viewer is attached, and body-chunk events
+/// (see [`BODY_CHUNK_TARGET`]) are never sent.
+pub struct BroadcastLayer {
+    tx: broadcast::Sender<LogRecord>,
+}
+impl BroadcastLayer {
+    pub fn new(tx: broadcast::Sender<LogRecord>) -> Self {
+        Self { tx }
+    }
+}
+impl<S: Subscriber> Layer<S> for BroadcastLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+        let meta = event.metadata();
+        // Streaming this event back to a `/logs` viewer would itself produce
+        // a body chunk on the `/logs` response, re-triggering the same event
+        // without bound; never rebroadcast it.
+        if meta.target() == BODY_CHUNK_TARGET {
+            return;
+        }
+        let mut message = String::new();
+        event.record(&mut MessageVisitor(&mut message));
+        let _ = self.tx.send(LogRecord {
+            level: *meta.level(),
+            target: meta.target().to_owned(),
+            message,
+        });
+    }
+}
+
+/// Tracks how many active `/logs` viewers currently want events at least as
+/// verbose as a given level, so [`BroadcastLayer`]'s own filter only opens
+/// the global max tracing level up to what's actually being watched, rather
+/// than leaving it permanently uncapped.
+#[derive(Default)]
+pub struct LevelFloor {
+    counts: Mutex<[usize; Self::LEVEL_COUNT]>,
+    current: AtomicUsize,
+}
+impl LevelFloor {
+    const LEVEL_COUNT: usize = 5;
+    fn index(level: Level) -> usize {
+        match level {
+            Level::ERROR => 0,
+            Level::WARN => 1,
+            Level::INFO => 2,
+            Level::DEBUG => 3,
+            Level::TRACE => 4,
+        }
+    }
+    pub(crate) fn enabled(&self, level: Level) -> bool {
+        Self::index(level) <= self.current.load(Ordering::Relaxed)
+    }
+    /// Opens the floor to admit `level` for as long as the returned guard is
+    /// held, then closes it back down once every guard admitting that level
+    /// has been dropped.
+    fn raise(floor: &Arc<Self>, level: Level) -> LevelFloorGuard {
+        let idx = Self::index(level);
+        let mut counts = floor.counts.lock().expect("poisoned");
+        counts[idx] += 1;
+        floor.recompute(&counts);
+        LevelFloorGuard { floor: floor.clone(), idx }
+    }
+    fn recompute(&self, counts: &[usize; Self::LEVEL_COUNT]) {
+        let highest = counts.iter().rposition(|&n| n > 0).unwrap_or(0);
+        self.current.store(highest, Ordering::Relaxed);
+    }
+}
+struct LevelFloorGuard {
+    floor: Arc<LevelFloor>,
+    idx: usize,
+}
+impl Drop for LevelFloorGuard {
+    fn drop(&mut self) {
+        let mut counts = self.floor.counts.lock().expect("poisoned");
+        counts[self.idx] -= 1;
+        self.floor.recompute(&counts);
+    }
+}
+struct MessageVisitor<'a>(&'a mut String);
+impl tracing::field::Visit for MessageVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else if self.0.is_empty() {
+            let _ = write!(self.0, "{}={:?}", field.name(), value);
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Directive {
+    target: Option<String>,
+    level: Level,
+}
+fn parse_directive(level: Option<&str>, target: Option<&str>) -> Result<Directive, String> {
+    let parse_level = |s: &str| s.parse::<Level>().map_err(|_| format!("invalid level `{s}`"));
+    let level = level.map(parse_level).transpose()?.unwrap_or(Level::INFO);
+    match target.and_then(|t| t.split_once('=')) {
+        Some((target, level)) => Ok(Directive {
+            target: Some(target.to_owned()),
+            level: parse_level(level)?,
+        }),
+        None => Ok(Directive {
+            target: target.map(str::to_owned),
+            level,
+        }),
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LogFormat {
+    #[default]
+    Fmt,
+    Profile,
+}
+
+#[derive(Deserialize)]
+pub struct LogParams {
+    level: Option<String>,
+    target: Option<String>,
+    #[serde(default)]
+    fmt: LogFormat,
+}
+
+/// Keeps `_guard` alive for exactly as long as the wrapped stream is, so the
+/// viewer's level floor closes back down when (and only when) the response
+/// body is dropped.
+struct WithLevelFloor<S> {
+    inner: S,
+    _guard: LevelFloorGuard,
+}
+impl<S> Stream for WithLevelFloor<S>
+where
+    S: Stream + Unpin,
+{
+    type Item = S::Item;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+/// `GET /logs?level=debug&target=tower_http=debug&fmt=profile` tails the
+/// proxy's own tracing output over HTTP until the client disconnects.
+pub async fn logs(State(state): State<Arc<AppState>>, Query(params): Query<LogParams>) -> Response {
+    let directive = match parse_directive(params.level.as_deref(), params.target.as_deref()) {
+        Ok(directive) => directive,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    let fmt = params.fmt;
+    let start = Instant::now();
+    let rx = state.log_tx.subscribe();
+    // Opens the broadcast layer's filter up to this viewer's level for as
+    // long as the response stays connected, then closes it back down.
+    let level_floor_guard = LevelFloor::raise(&state.level_floor, directive.level);
+    // Boxed so the `!Unpin` async filter is `Unpin`, as the wrappers below need.
+    let lines = Box::pin(BroadcastStream::new(rx).filter_map(move |item| {
+        let directive = directive.clone();
+        async move {
+            match item {
+                Ok(record) if record.included_by(&directive) => {
+                    Some(Ok::<_, Infallible>(record.render(fmt, start)))
+                }
+                Ok(_) | Err(BroadcastStreamRecvError::Lagged(_)) => None,
+            }
+        }
+    }));
+    let body = Body::from_stream(WithLevelFloor {
+        inner: StreamWithLoggedEnd::new(lines, Span::current()),
+        _guard: level_floor_guard,
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(body)
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
@@ -1,18 +1,29 @@
+mod logs;
 mod mw;
+mod proxy_protocol;
+mod stream;
 use axum::{
     Extension, Router,
     body::{Body, Bytes},
-    extract::{ConnectInfo, State, connect_info::IntoMakeServiceWithConnectInfo},
+    extract::{ConnectInfo, Path, State, connect_info::IntoMakeServiceWithConnectInfo},
     http::{Request, Response, StatusCode},
     response::IntoResponse,
     routing::get,
+    serve::Listener,
 };
+use axum_server::{Handle, tls_rustls::RustlsConfig};
 use clap::Parser;
 use diqwest::WithDigestAuth;
 use futures::FutureExt;
+use logs::BroadcastLayer;
 use mw::LayerTraceResponseEnd;
+use opentelemetry::{KeyValue, global};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{Resource, propagation::TraceContextPropagator, trace as sdktrace};
+use proxy_protocol::ProxyProtocolListener;
 use reqwest::{Client, ClientBuilder};
 use std::{
+    collections::BTreeMap,
     net::SocketAddr,
     sync::{
         Arc,
@@ -20,11 +31,14 @@ use std::{
     },
     time::Duration,
 };
-use tokio::{net::TcpListener, signal};
+use stream::{StreamHub, SubscribeError};
+use tokio::{net::TcpListener, signal, sync::broadcast};
 use tower_http::trace::TraceLayer;
 use tracing::Span;
 use tracing_appender::rolling;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry, filter::filter_fn, layer::SubscriberExt, util::SubscriberInitExt,
+};
 
 #[derive(Parser)]
 #[command(version)]
@@ -32,12 +46,14 @@ struct Opt {
     #[clap(short, long, default_value = "127.0.0.1:11111")]
     binding: String,
 
-    /// upstream mjpeg url
-    url: String,
-    /// upstream mjpeg server username
+    /// camera to proxy, as name=url[,user,pass]; repeat to serve multiple cameras.
+    /// user/pass fall back to --username/--password when omitted
+    #[clap(short, long = "camera", value_parser = CameraSpec::parse, required = true)]
+    cameras: Vec<CameraSpec>,
+    /// default upstream mjpeg server username, for cameras that don't specify one
     #[clap(short, long, env = "MDAP_USERNAME", default_value = "username")]
     username: String,
-    /// upstream mjpeg server password
+    /// default upstream mjpeg server password, for cameras that don't specify one
     #[clap(short, long, env = "MDAP_PASSWORD", default_value = "password")]
     password: String,
 
@@ -48,10 +64,70 @@ struct Opt {
     /// enable logging to daily file. supply a value to override the default log directory [default: logs]
     #[clap(short, long, num_args=0..=1, require_equals=true, default_missing_value = "logs")]
     log_dir: Option<String>,
+
+    /// PEM certificate chain for TLS termination; requires --tls-key
+    #[clap(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+    /// PEM private key for TLS termination; requires --tls-cert
+    #[clap(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+
+    /// read a PROXY protocol v1/v2 header off each connection to recover the real client address.
+    /// not supported together with --tls-cert/--tls-key: PROXY protocol is peeled off the raw
+    /// TCP stream before TLS termination, which this binary doesn't yet implement
+    #[clap(long, conflicts_with_all = ["tls_cert", "tls_key"])]
+    proxy_protocol: bool,
+
+    /// export traces via OTLP to this collector endpoint, alongside fmt/file logging
+    #[clap(long, env = "MDAP_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+}
+/// One `--camera name=url[,user,pass]` occurrence.
+#[derive(Clone)]
+struct CameraSpec {
+    name: String,
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+}
+impl CameraSpec {
+    fn parse(s: &str) -> Result<Self, String> {
+        let (name, rest) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=url[,user,pass], got `{s}`"))?;
+        if name.is_empty() {
+            return Err(format!("camera name must not be empty in `{s}`"));
+        }
+        let mut parts = rest.splitn(3, ',');
+        let url = parts
+            .next()
+            .filter(|url| !url.is_empty())
+            .ok_or_else(|| format!("missing url in `{s}`"))?
+            .to_owned();
+        Ok(Self {
+            name: name.to_owned(),
+            url,
+            // An empty field (`name=url,,pass`) means "use the fallback", not
+            // "use an empty string" — otherwise it would override --username/
+            // --password with "" instead of falling back to them.
+            username: parts.next().filter(|s| !s.is_empty()).map(str::to_owned),
+            password: parts.next().filter(|s| !s.is_empty()).map(str::to_owned),
+        })
+    }
+}
+/// A single camera's upstream config and shared stream.
+struct Camera {
+    url: String,
+    username: String,
+    password: String,
+    hub: StreamHub,
 }
 struct AppState {
     client: Client,
     opt: Opt,
+    cameras: BTreeMap<String, Camera>,
+    log_tx: broadcast::Sender<logs::LogRecord>,
+    level_floor: Arc<logs::LevelFloor>,
 }
 impl AppState {
     fn new() -> Arc<Self> {
@@ -60,7 +136,26 @@ impl AppState {
             .danger_accept_invalid_certs(opt.insecure)
             .build()
             .expect("failed to build client");
-        Arc::new(Self { opt, client })
+        let cameras = opt
+            .cameras
+            .iter()
+            .map(|c| {
+                let camera = Camera {
+                    url: c.url.clone(),
+                    username: c.username.clone().unwrap_or_else(|| opt.username.clone()),
+                    password: c.password.clone().unwrap_or_else(|| opt.password.clone()),
+                    hub: StreamHub::default(),
+                };
+                (c.name.clone(), camera)
+            })
+            .collect();
+        Arc::new(Self {
+            opt,
+            client,
+            cameras,
+            log_tx: logs::channel(),
+            level_floor: Arc::new(logs::LevelFloor::default()),
+        })
     }
 }
 #[derive(Clone)]
@@ -76,24 +171,61 @@ impl RqId {
         self.0.fetch_add(1, Ordering::Relaxed)
     }
 }
+/// Builds the `tracing-opentelemetry` layer exporting spans (and the events
+/// recorded on them, e.g. `on_body_chunk`'s latency) to `endpoint` via OTLP.
+fn otel_layer<S>(endpoint: &str) -> impl Layer<S>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+        .with_trace_config(
+            sdktrace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                env!("CARGO_CRATE_NAME"),
+            )])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .expect("failed to install otlp pipeline");
+    tracing_opentelemetry::layer().with_tracer(tracer)
+}
 fn setup_tracing(state: Arc<AppState>) {
-    let sub = tracing_subscriber::fmt().with_env_filter(
-        EnvFilter::try_from_default_env()
-            .or_else(|_| {
-                EnvFilter::try_new(format!(
-                    "{}=debug,tower_http=debug,axum::rejection=trace",
-                    env!("CARGO_CRATE_NAME")
-                ))
-            })
-            .expect("tracing setup failed"),
-    );
-    if let Some(dir) = &state.opt.log_dir {
-        let file = rolling::daily(dir, "");
-        sub.with_writer(file).with_ansi(false).init();
+    let fmt_filter = EnvFilter::try_from_default_env()
+        .or_else(|_| {
+            EnvFilter::try_new(format!(
+                "{}=debug,tower_http=debug,axum::rejection=trace",
+                env!("CARGO_CRATE_NAME")
+            ))
+        })
+        .expect("tracing setup failed");
+    // The fmt layer keeps its usual filter. The broadcast layer's own filter
+    // tracks `level_floor`, which `/logs` raises only while a viewer is
+    // actually attached — left unfiltered, it would permanently raise the
+    // global max tracing level to TRACE, making every `trace!` call (e.g.
+    // the per-chunk `on_body_chunk` hook) build and dispatch an event even
+    // with zero `/logs` viewers connected.
+    let fmt_layer = if let Some(dir) = &state.opt.log_dir {
+        tracing_subscriber::fmt::layer()
+            .with_writer(rolling::daily(dir, ""))
+            .with_ansi(false)
+            .boxed()
     } else {
-        sub.init();
-    }
+        tracing_subscriber::fmt::layer().boxed()
+    };
+    let level_floor = state.level_floor.clone();
+    Registry::default()
+        .with(fmt_layer.with_filter(fmt_filter))
+        .with(BroadcastLayer::new(state.log_tx.clone()).with_filter(filter_fn(move |meta| level_floor.enabled(*meta.level()))))
+        .with(state.opt.otlp_endpoint.as_deref().map(otel_layer))
+        .init();
 }
+/// Target used by [`LayerTrace::on_body_chunk`]'s per-frame trace event, so
+/// `/logs` can recognize and exclude it: streaming that event back to a
+/// `/logs` viewer would itself produce a body chunk on the `/logs` response,
+/// re-triggering the same event without bound.
+pub(crate) const BODY_CHUNK_TARGET: &str = "mdap::body_chunk";
 trait LayerTrace {
     fn layer_trace(self) -> Self;
     fn make_span_with(request: &Request<Body>) -> Span;
@@ -123,6 +255,7 @@ impl LayerTrace for Router {
     }
     fn on_body_chunk(chunk: &Bytes, latency: Duration, _: &Span) {
         tracing::trace!(
+            target: BODY_CHUNK_TARGET,
             size_bytes = %chunk.len(),
             latency = ?latency,
         )
@@ -130,7 +263,9 @@ impl LayerTrace for Router {
 }
 fn mk_app(state: Arc<AppState>) -> IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
     Router::new()
-        .route("/", get(mjpeg))
+        .route("/", get(index))
+        .route("/camera/{name}", get(mjpeg))
+        .route("/logs", get(logs::logs))
         .with_state(state)
         .layer_trace_response_end()
         .layer_trace()
@@ -142,48 +277,93 @@ async fn mk_listener(state: Arc<AppState>) -> TcpListener {
         .await
         .expect("bind failed")
 }
+/// Loads a [`RustlsConfig`] from `--tls-cert`/`--tls-key`, if both were given.
+async fn tls_config(opt: &Opt) -> Option<RustlsConfig> {
+    let (cert, key) = match (&opt.tls_cert, &opt.tls_key) {
+        (Some(cert), Some(key)) => (cert, key),
+        _ => return None,
+    };
+    Some(
+        RustlsConfig::from_pem_file(cert, key)
+            .await
+            .expect("failed to load tls cert/key"),
+    )
+}
+async fn shutdown_on_ctrl_c(handle: Handle) {
+    signal::ctrl_c().await.expect("ctrl_c failed");
+    handle.graceful_shutdown(None);
+}
 #[tokio::main]
 async fn main() {
     let state = AppState::new();
     setup_tracing(state.clone());
     let app = mk_app(state.clone());
-    let listener = mk_listener(state.clone()).await;
-    tracing::debug!(
-        listening_on = %listener.local_addr().expect("local_addr"),
-        proxying_to = %&state.opt.url
-    );
-    axum::serve(listener, app)
-        .with_graceful_shutdown(signal::ctrl_c().map(|_| ()))
-        .await
-        .expect("serve failed");
+    tracing::debug!(cameras = %state.cameras.keys().cloned().collect::<Vec<_>>().join(", "));
+    match tls_config(&state.opt).await {
+        Some(tls) => {
+            // clap rejects --proxy-protocol together with --tls-cert/--tls-key,
+            // so there's nothing to reconcile between them here.
+            let addr: SocketAddr = state.opt.binding.parse().expect("invalid binding address");
+            let handle = Handle::new();
+            tokio::spawn(shutdown_on_ctrl_c(handle.clone()));
+            tracing::debug!(listening_on = %addr, tls = true);
+            axum_server::bind_rustls(addr, tls)
+                .handle(handle)
+                .serve(app)
+                .await
+                .expect("serve failed");
+        }
+        None => {
+            let listener =
+                ProxyProtocolListener::new(mk_listener(state.clone()).await, state.opt.proxy_protocol);
+            tracing::debug!(listening_on = %listener.local_addr().expect("local_addr"));
+            axum::serve(listener, app)
+                .with_graceful_shutdown(signal::ctrl_c().map(|_| ()))
+                .await
+                .expect("serve failed");
+        }
+    }
+    global::shutdown_tracer_provider();
     tracing::debug!("end");
 }
-async fn mjpeg(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+async fn index(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    state
+        .cameras
+        .keys()
+        .map(|name| format!("{name}\n"))
+        .collect::<String>()
+}
+async fn mjpeg(Path(name): Path<String>, State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let Some(camera) = state.cameras.get(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
     let err_bg = StatusCode::BAD_GATEWAY.into_response();
-    let u_rs = match state
-        .client
-        .get(&state.opt.url)
-        .send_with_digest_auth(&state.opt.username, &state.opt.password)
-        .await
-    {
-        Ok(u_rs) => u_rs,
-        Err(err) => {
+    let sub = camera
+        .hub
+        .subscribe(|| {
+            state
+                .client
+                .get(&camera.url)
+                .send_with_digest_auth(&camera.username, &camera.password)
+        })
+        .await;
+    let sub = match sub {
+        Ok(sub) => sub,
+        Err(SubscribeError::Connect(err)) => {
             tracing::error!(upstream_request_error = ?err);
             return err_bg;
         }
+        Err(SubscribeError::Status(_)) => return err_bg,
     };
-    if u_rs.status() != StatusCode::OK {
-        return err_bg;
-    }
     let srv_err = StatusCode::INTERNAL_SERVER_ERROR.into_response();
     let mut b = Response::builder();
     if let Some(h) = b.headers_mut() {
-        *h = u_rs.headers().clone();
+        *h = sub.headers.clone();
     } else {
         tracing::error!("headers_mut failed");
         return srv_err;
     }
-    if let Ok(rs) = b.body(Body::from_stream(u_rs.bytes_stream())) {
+    if let Ok(rs) = b.body(Body::from_stream(sub.into_body_stream())) {
         rs
     } else {
         tracing::error!("response build failed");
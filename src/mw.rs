@@ -13,12 +13,14 @@ use std::{
     task::{Context, Poll},
 };
 use tracing::Span;
-struct StreamWithLoggedEnd<S> {
+/// Wraps a stream to log when it ends (including when it is simply dropped,
+/// e.g. because the client disconnected). Shared with the `/logs` route.
+pub(crate) struct StreamWithLoggedEnd<S> {
     inner: S,
     span: Span,
 }
 impl<S> StreamWithLoggedEnd<S> {
-    fn new(inner: S, span: Span) -> Self {
+    pub(crate) fn new(inner: S, span: Span) -> Self {
         Self { inner, span }
     }
 }
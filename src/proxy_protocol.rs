@@ -0,0 +1,234 @@
+use axum::serve::Listener;
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::timeout,
+};
+
+/// The longest a PROXY protocol v1 line is allowed to be (the spec caps it at 107 bytes).
+const MAX_V1_LINE: usize = 107;
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// A header that hasn't arrived by now is either a stalled/malicious peer or
+/// not PROXY protocol at all; give up on it rather than let it sit forever.
+const HEADER_READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bounds how many accepted-but-not-yet-handed-off connections can queue up.
+const ACCEPT_QUEUE_DEPTH: usize = 16;
+
+/// A [`TcpListener`] that, when `enabled`, reads and strips a PROXY protocol
+/// v1/v2 header off every accepted connection before handing it to axum, so
+/// `ConnectInfo<SocketAddr>` (and therefore `make_span_with`) sees the real
+/// client address instead of the load balancer's.
+///
+/// When disabled (the default), connections are accepted and wrapped inline
+/// with no header parsing, no background task, and no channel hop — the
+/// proxy-protocol machinery below is entirely skipped.
+///
+/// When enabled, header parsing happens in its own task per connection
+/// rather than inline in [`Listener::accept`], so one slow or malicious peer
+/// trickling in its header byte by byte can't stall every other connection's
+/// accept.
+pub struct ProxyProtocolListener {
+    local_addr: SocketAddr,
+    inner: Inner,
+}
+enum Inner {
+    Plain(TcpListener),
+    ProxyProtocol(mpsc::Receiver<(PrefixedStream<TcpStream>, SocketAddr)>),
+}
+impl ProxyProtocolListener {
+    pub fn new(inner: TcpListener, enabled: bool) -> Self {
+        let local_addr = inner.local_addr().expect("local_addr");
+        let inner = if enabled {
+            let (tx, rx) = mpsc::channel(ACCEPT_QUEUE_DEPTH);
+            tokio::spawn(accept_loop(inner, tx));
+            Inner::ProxyProtocol(rx)
+        } else {
+            Inner::Plain(inner)
+        };
+        Self { local_addr, inner }
+    }
+}
+impl Listener for ProxyProtocolListener {
+    type Io = PrefixedStream<TcpStream>;
+    type Addr = SocketAddr;
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match &mut self.inner {
+            Inner::Plain(listener) => loop {
+                match listener.accept().await {
+                    Ok((stream, addr)) => return (PrefixedStream::new(stream, Vec::new()), addr),
+                    Err(err) => tracing::warn!(accept_error = ?err, "failed to accept connection"),
+                }
+            },
+            Inner::ProxyProtocol(accepted) => accepted.recv().await.expect("accept loop task died"),
+        }
+    }
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// Accepts raw TCP connections as fast as the kernel hands them over, and
+/// spawns header parsing off to its own task per connection so that work
+/// never blocks the next `accept()`.
+async fn accept_loop(inner: TcpListener, tx: mpsc::Sender<(PrefixedStream<TcpStream>, SocketAddr)>) {
+    loop {
+        let (mut stream, peer_addr) = match inner.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::warn!(accept_error = ?err, "failed to accept connection");
+                continue;
+            }
+        };
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let accepted = match timeout(HEADER_READ_TIMEOUT, peel_proxy_header(&mut stream)).await {
+                Ok(Ok((source, leftover))) => {
+                    (PrefixedStream::new(stream, leftover), source.unwrap_or(peer_addr))
+                }
+                Ok(Err(err)) => {
+                    tracing::warn!(proxy_protocol_error = ?err, %peer_addr, "failed to read proxy protocol header");
+                    return;
+                }
+                Err(_) => {
+                    tracing::warn!(%peer_addr, "timed out waiting for proxy protocol header");
+                    return;
+                }
+            };
+            // The receiver only disappears when the listener itself is
+            // dropped, at which point there's nowhere left to send this to.
+            let _ = tx.send(accepted).await;
+        });
+    }
+}
+
+/// Reads a PROXY protocol header (v1 or v2) off `stream`, returning the
+/// decoded source address (if any) and any bytes read past the header that
+/// belong to the connection's actual traffic.
+async fn peel_proxy_header<S>(stream: &mut S) -> io::Result<(Option<SocketAddr>, Vec<u8>)>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = Vec::with_capacity(16);
+    loop {
+        let mut byte = [0u8; 1];
+        if stream.read(&mut byte).await? == 0 {
+            return Ok((None, buf));
+        }
+        buf.push(byte[0]);
+        if buf.len() >= V2_SIGNATURE.len() {
+            if buf[..V2_SIGNATURE.len()] == V2_SIGNATURE {
+                return read_v2(stream, buf).await;
+            }
+        } else if V2_SIGNATURE.starts_with(&buf) {
+            // Still matches the v2 signature so far; keep reading instead of
+            // mistaking a leading "\r\n" for a v1 line terminator below.
+            continue;
+        }
+        if buf.ends_with(b"\r\n") {
+            // A line that isn't a valid `PROXY ...` header is still consumed
+            // from the socket; hand it back as leftover so the caller can
+            // forward it rather than silently eating a non-PROXY client's
+            // first line and corrupting its request.
+            return Ok(match parse_v1(&buf) {
+                Some(source) => (Some(source), Vec::new()),
+                // `PROXY UNKNOWN` is a valid header that carries no address.
+                None if buf.starts_with(b"PROXY UNKNOWN") => (None, Vec::new()),
+                None => (None, buf),
+            });
+        }
+        if buf.len() > MAX_V1_LINE {
+            return Ok((None, buf));
+        }
+    }
+}
+
+fn parse_v1(line: &[u8]) -> Option<SocketAddr> {
+    let line = std::str::from_utf8(line).ok()?.trim_end();
+    let mut parts = line.strip_prefix("PROXY ")?.split(' ');
+    let _proto = parts.next()?;
+    let src_ip = parts.next()?;
+    let _dst_ip = parts.next()?;
+    let src_port = parts.next()?;
+    Some(SocketAddr::new(src_ip.parse().ok()?, src_port.parse().ok()?))
+}
+
+async fn read_v2<S>(stream: &mut S, mut buf: Vec<u8>) -> io::Result<(Option<SocketAddr>, Vec<u8>)>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut fixed = [0u8; 4];
+    stream.read_exact(&mut fixed).await?;
+    buf.extend_from_slice(&fixed);
+    let command = fixed[0] & 0x0f;
+    let family_protocol = fixed[1];
+    let len = u16::from_be_bytes([fixed[2], fixed[3]]) as usize;
+    let mut addresses = vec![0u8; len];
+    stream.read_exact(&mut addresses).await?;
+    buf.extend_from_slice(&addresses);
+    // Command 0x0 (LOCAL, e.g. a load balancer health check) carries no real
+    // client and must keep the real peer address; only 0x1 (PROXY) does.
+    let source = if command != 0x1 {
+        None
+    } else {
+        match family_protocol >> 4 {
+            0x1 if addresses.len() >= 12 => {
+                let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+                let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+                Some(SocketAddr::new(ip.into(), port))
+            }
+            0x2 if addresses.len() >= 36 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&addresses[..16]);
+                let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+                Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port))
+            }
+            _ => None,
+        }
+    };
+    // The header's declared length was read exactly, so nothing past it was consumed.
+    Ok((source, Vec::new()))
+}
+
+/// Wraps a stream, replaying `prefix` (bytes already consumed while scanning
+/// for a PROXY protocol header) before resuming reads from `inner`.
+pub struct PrefixedStream<S> {
+    inner: S,
+    prefix: Vec<u8>,
+    prefix_pos: usize,
+}
+impl<S> PrefixedStream<S> {
+    fn new(inner: S, prefix: Vec<u8>) -> Self {
+        Self { inner, prefix, prefix_pos: 0 }
+    }
+}
+impl<S: AsyncRead + Unpin> AsyncRead for PrefixedStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.prefix_pos < self.prefix.len() {
+            let remaining = &self.prefix[self.prefix_pos..];
+            let n = remaining.len().min(buf.remaining());
+            buf.put_slice(&remaining[..n]);
+            self.prefix_pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+impl<S: AsyncWrite + Unpin> AsyncWrite for PrefixedStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,298 @@
+use axum::body::Bytes;
+use futures::{Stream, StreamExt};
+use reqwest::{Response as UpstreamResponse, StatusCode, header::HeaderMap};
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tokio::sync::{broadcast, watch};
+use tokio_stream::wrappers::{BroadcastStream, errors::BroadcastStreamRecvError};
+
+/// Depth of the per-camera fan-out channel, in upstream byte chunks. A slow
+/// client falls behind and starts dropping chunks (see
+/// [`BroadcastStreamRecvError::Lagged`]) rather than holding up the others;
+/// a dropped chunk can cut into a frame, and the stream is not re-aligned to
+/// the next boundary afterwards.
+const CHANNEL_CAPACITY: usize = 16;
+
+/// The single upstream connection backing every subscribed client.
+struct SharedStream {
+    headers: HeaderMap,
+    boundary: Vec<u8>,
+    tx: broadcast::Sender<Bytes>,
+    shutdown: watch::Sender<bool>,
+}
+
+/// Error produced while establishing (or reusing) a [`SharedStream`].
+pub enum SubscribeError<E> {
+    Connect(E),
+    Status(StatusCode),
+}
+
+/// One client's handle onto a [`SharedStream`]. Dropping it (e.g. when the
+/// client disconnects) releases the subscriber slot and, if it was the last
+/// one, tears down the upstream connection.
+pub struct Subscription {
+    pub headers: HeaderMap,
+    rx: broadcast::Receiver<Bytes>,
+    boundary: Vec<u8>,
+    guard: SubscriberGuard,
+}
+
+/// The hub's state: the currently live stream, if any, paired with how many
+/// subscribers are attached to *that* stream. Keeping the pair behind one
+/// lock means attaching, detaching, and replacing the stream are each a
+/// single atomic step, so a subscriber can never observe (or decrement a
+/// count against) a stream that's already been replaced or torn down.
+#[derive(Default)]
+struct HubState {
+    live: Option<(Arc<SharedStream>, usize)>,
+}
+
+struct SubscriberGuard {
+    state: Arc<Mutex<HubState>>,
+    shared: Arc<SharedStream>,
+}
+
+impl Drop for SubscriberGuard {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().expect("poisoned");
+        let last_one_out = match &mut state.live {
+            Some((current, count)) if Arc::ptr_eq(current, &self.shared) => {
+                *count -= 1;
+                *count == 0
+            }
+            // The stream we were attached to has already been replaced or
+            // torn down by someone else; nothing left for us to do.
+            _ => false,
+        };
+        if last_one_out {
+            state.live = None;
+            let _ = self.shared.shutdown.send(true);
+        }
+    }
+}
+
+impl Subscription {
+    /// Turns this subscription into the byte stream the response body is
+    /// built from: lagged chunks are dropped, late joiners are fast-forwarded
+    /// to the next multipart boundary, and the subscriber slot is held for as
+    /// long as the stream is.
+    pub fn into_body_stream(self) -> impl Stream<Item = Result<Bytes, Infallible>> + Send + 'static {
+        let Subscription { rx, boundary, guard, .. } = self;
+        // Boxed so the `!Unpin` async filter is `Unpin`, as `BoundaryAligned` needs.
+        let chunks = Box::pin(BroadcastStream::new(rx).filter_map(|item| async move {
+            match item {
+                Ok(chunk) => Some(chunk),
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "client fell behind, dropping buffered chunks");
+                    None
+                }
+            }
+        }));
+        GuardedStream {
+            inner: BoundaryAligned::new(chunks, boundary).map(Ok),
+            _guard: guard,
+        }
+    }
+}
+
+/// Holds at most one live upstream connection, spawning it lazily on the
+/// first subscriber and tearing it down once the last one disconnects.
+#[derive(Default)]
+pub struct StreamHub {
+    state: Arc<Mutex<HubState>>,
+}
+
+impl StreamHub {
+    /// Subscribes to the shared stream, connecting upstream via `connect`
+    /// first if no connection is currently live.
+    pub async fn subscribe<F, Fut, E>(&self, connect: F) -> Result<Subscription, SubscribeError<E>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<UpstreamResponse, E>>,
+    {
+        if let Some(shared) = self.try_attach() {
+            return Ok(Self::subscription(self.state.clone(), shared));
+        }
+        // Connect without holding the lock: a slow or unreachable camera
+        // should only block this one viewer, not every other viewer already
+        // attached (or racing in) on the same camera.
+        let u_rs = connect().await.map_err(SubscribeError::Connect)?;
+        if u_rs.status() != StatusCode::OK {
+            return Err(SubscribeError::Status(u_rs.status()));
+        }
+        let mut state = self.state.lock().expect("poisoned");
+        let shared = match &mut state.live {
+            // Someone else raced us and already spawned a stream while we
+            // were connecting; join it and let our own connection be dropped.
+            Some((shared, count)) => {
+                *count += 1;
+                shared.clone()
+            }
+            None => {
+                let shared = Self::spawn(self.state.clone(), u_rs);
+                state.live = Some((shared.clone(), 1));
+                shared
+            }
+        };
+        drop(state);
+        Ok(Self::subscription(self.state.clone(), shared))
+    }
+
+    /// Attaches to the currently live stream, if any, incrementing its
+    /// subscriber count in the same step so a racing teardown can't miss us.
+    fn try_attach(&self) -> Option<Arc<SharedStream>> {
+        let mut state = self.state.lock().expect("poisoned");
+        let (shared, count) = state.live.as_mut()?;
+        *count += 1;
+        Some(shared.clone())
+    }
+
+    fn subscription(state: Arc<Mutex<HubState>>, shared: Arc<SharedStream>) -> Subscription {
+        Subscription {
+            headers: shared.headers.clone(),
+            rx: shared.tx.subscribe(),
+            boundary: shared.boundary.clone(),
+            guard: SubscriberGuard { state, shared },
+        }
+    }
+
+    fn spawn(state: Arc<Mutex<HubState>>, u_rs: UpstreamResponse) -> Arc<SharedStream> {
+        let headers = u_rs.headers().clone();
+        let boundary = headers
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_boundary)
+            .map(|b| format!("--{b}").into_bytes())
+            .unwrap_or_default();
+        let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let (shutdown, mut shutdown_rx) = watch::channel(false);
+        let shared = Arc::new(SharedStream {
+            headers,
+            boundary,
+            tx: tx.clone(),
+            shutdown,
+        });
+        let shared_for_task = shared.clone();
+        tokio::spawn(async move {
+            let mut bytes = u_rs.bytes_stream();
+            loop {
+                tokio::select! {
+                    _ = shutdown_rx.changed() => {
+                        tracing::debug!("last subscriber gone, tearing down upstream connection");
+                        break;
+                    }
+                    chunk = bytes.next() => match chunk {
+                        Some(Ok(chunk)) => {
+                            // A send error just means every subscriber dropped between
+                            // chunks; the shutdown watch above will catch up shortly.
+                            let _ = tx.send(chunk);
+                        }
+                        Some(Err(err)) => {
+                            tracing::error!(upstream_stream_error = ?err);
+                            break;
+                        }
+                        None => {
+                            tracing::debug!("upstream stream ended");
+                            break;
+                        }
+                    },
+                }
+            }
+            // Only clear the slot if it's still us: a subscriber may have
+            // raced this teardown and already installed a fresh stream.
+            let mut state = state.lock().expect("poisoned");
+            if matches!(&state.live, Some((current, _)) if Arc::ptr_eq(current, &shared_for_task)) {
+                state.live = None;
+            }
+        });
+        shared
+    }
+}
+
+/// Extracts the `boundary=` parameter from a `multipart/x-mixed-replace`
+/// `Content-Type` value.
+fn parse_boundary(content_type: &str) -> Option<String> {
+    content_type.split(';').find_map(|part| {
+        part.trim()
+            .strip_prefix("boundary=")
+            .map(|b| b.trim_matches('"').to_owned())
+    })
+}
+
+/// Wraps a chunk stream so that, until the boundary marker is seen, incoming
+/// bytes are buffered and discarded rather than forwarded. This keeps a late
+/// joiner from being handed the tail end of a frame already in flight.
+struct BoundaryAligned<S> {
+    inner: S,
+    boundary: Vec<u8>,
+    tail: Vec<u8>,
+    aligned: bool,
+}
+
+impl<S> BoundaryAligned<S> {
+    fn new(inner: S, boundary: Vec<u8>) -> Self {
+        let aligned = boundary.is_empty();
+        Self { inner, boundary, tail: Vec::new(), aligned }
+    }
+}
+
+impl<S> Stream for BoundaryAligned<S>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    type Item = Bytes;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if self.aligned {
+                return Pin::new(&mut self.inner).poll_next(cx);
+            }
+            match Pin::new(&mut self.inner).poll_next(cx) {
+                Poll::Ready(Some(chunk)) => {
+                    self.tail.extend_from_slice(&chunk);
+                    if let Some(pos) = find_subslice(&self.tail, &self.boundary) {
+                        self.aligned = true;
+                        let aligned = Bytes::copy_from_slice(&self.tail[pos..]);
+                        self.tail.clear();
+                        return Poll::Ready(Some(aligned));
+                    }
+                    // Only the last (boundary.len() - 1) bytes could still be a
+                    // split match once more data arrives; drop the rest so `tail`
+                    // doesn't grow without bound while waiting for a boundary.
+                    let keep_from = self.tail.len().saturating_sub(self.boundary.len() - 1);
+                    self.tail.drain(..keep_from);
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Keeps `_guard` alive for exactly as long as the wrapped stream is, so the
+/// subscriber slot is released when (and only when) the response body is
+/// dropped.
+struct GuardedStream<S> {
+    inner: S,
+    _guard: SubscriberGuard,
+}
+
+impl<S> Stream for GuardedStream<S>
+where
+    S: Stream + Unpin,
+{
+    type Item = S::Item;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}